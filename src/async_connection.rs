@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::async_serialize::{AsyncNixDeserialize, AsyncNixSerialize};
+use crate::progress::{ProgressHandler, StderrProgressHandler};
+use crate::wire::{
+    self, Error, Field, Result, PROTOCOL_VERSION, WORKER_MAGIC_1, WORKER_MAGIC_2,
+    STDERR_LAST, STDERR_RESULT, STDERR_START_ACTIVITY, STDERR_STOP_ACTIVITY, STDERR_WRITE,
+};
+
+/// Async mirror of [`crate::connection::NixStoreConnection`], for use inside
+/// tokio-based services (substituter frontends, build coordinators) that
+/// can't afford to block a worker thread per connection.
+pub struct AsyncNixStoreConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    connection: T,
+    /// Raw version the daemon advertised during the handshake.
+    daemon_version: u64,
+    /// `min(PROTOCOL_VERSION, daemon_version)`, the version actually spoken
+    /// on this connection; every op consults this to decide which optional
+    /// fields to read or write.
+    protocol_version: u64,
+    daemon_nix_version: String,
+    progress_handler: Box<dyn ProgressHandler + Send>,
+}
+
+impl<T> AsyncNixStoreConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    async fn read_u64(&mut self) -> Result<u64> {
+        self.connection.read_u64_le().await.map_err(Error::Read)
+    }
+
+    async fn write_u64(&mut self, value: u64) -> Result<()> {
+        self.connection
+            .write_u64_le(value)
+            .await
+            .map_err(Error::Write)
+    }
+
+    async fn init(&mut self) -> Result<()> {
+        self.write_u64(WORKER_MAGIC_1).await?;
+        if self.read_u64().await? != WORKER_MAGIC_2 {
+            return Err(Error::ProtocolMismatch);
+        }
+        self.daemon_version = self.read_u64().await?;
+        if wire::protocol_major(self.daemon_version) != wire::protocol_major(PROTOCOL_VERSION)
+            || self.daemon_version < wire::PROTOCOL_VERSION_MIN_SUPPORTED
+        {
+            return Err(Error::UnsupportedProtocolVersion(self.daemon_version));
+        }
+        self.write_u64(PROTOCOL_VERSION).await?;
+        self.protocol_version = wire::negotiate_version(PROTOCOL_VERSION, self.daemon_version);
+        if self.protocol_version >= wire::PROTOCOL_VERSION_CPU_AFFINITY {
+            self.write_u64(0).await?; // obsolete CPU affinity
+        }
+        if self.protocol_version >= wire::PROTOCOL_VERSION_RESERVE_SPACE {
+            self.write_u64(0).await?; // obsolete reserveSpace
+        }
+        self.connection.flush().await.map_err(Error::Flush)?;
+        self.daemon_nix_version = self.read_string().await?;
+        if self.protocol_version >= wire::PROTOCOL_VERSION_FEATURES {
+            let num_features = self.read_u64().await?;
+            for _ in 0..num_features {
+                let _feature = self.read_string().await?;
+            }
+        }
+        self.process_stderr().await?;
+        Ok(())
+    }
+
+    async fn read_string(&mut self) -> Result<String> {
+        let len = self.read_u64().await? as usize;
+        let mut buf = vec![0u8; len + wire::padding(len)];
+        self.connection.read_exact(&mut buf).await.map_err(Error::Read)?;
+        buf.truncate(len);
+        String::from_utf8(buf).map_err(Error::ParseUTF8)
+    }
+
+    async fn read_fields(&mut self) -> Result<Vec<Field>> {
+        let num_fields = self.read_u64().await?;
+        let mut result = Vec::with_capacity(num_fields as usize);
+        for _ in 0..num_fields {
+            let field_type = self.read_u64().await?;
+            result.push(match field_type {
+                0 => Field::Int(self.read_u64().await?),
+                1 => Field::String(self.read_string().await?),
+                _ => return Err(Error::UnsupportedFieldType(field_type)),
+            });
+        }
+        Ok(result)
+    }
+
+    async fn process_stderr(&mut self) -> Result<()> {
+        self.connection.flush().await.map_err(Error::Flush)?;
+
+        loop {
+            match self.read_u64().await? {
+                STDERR_WRITE => {
+                    let s = self.read_string().await?;
+                    self.progress_handler.on_message(&s);
+                }
+                STDERR_START_ACTIVITY => {
+                    let activity_id = self.read_u64().await?;
+                    let level = self.read_u64().await?;
+                    let activity_type = self.read_u64().await?;
+                    let description = self.read_string().await?;
+                    let fields = self.read_fields().await?;
+                    let parent_activity_id = self.read_u64().await?;
+                    self.progress_handler.on_start_activity(
+                        activity_id,
+                        level,
+                        activity_type,
+                        &description,
+                        &fields,
+                        parent_activity_id,
+                    );
+                }
+                STDERR_STOP_ACTIVITY => {
+                    let activity_id = self.read_u64().await?;
+                    self.progress_handler.on_stop_activity(activity_id);
+                }
+                STDERR_LAST => break,
+                STDERR_RESULT => {
+                    let activity_id = self.read_u64().await?;
+                    let result_type = self.read_u64().await?;
+                    let fields = self.read_fields().await?;
+                    self.progress_handler.on_result(activity_id, result_type, &fields);
+                }
+                n => return Err(Error::UnsupportedStderrMessage(n)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Install a handler to receive progress/logging traffic instead of the
+    /// default of writing messages to stderr and discarding the rest.
+    pub fn set_progress_handler(&mut self, handler: impl ProgressHandler + Send + 'static) {
+        self.progress_handler = Box::new(handler);
+    }
+
+    pub async fn connect(connection: T) -> Result<Self> {
+        let mut result = Self {
+            connection,
+            daemon_version: 0,
+            protocol_version: 0,
+            daemon_nix_version: String::from(""),
+            progress_handler: Box::new(StderrProgressHandler),
+        };
+        result.init().await?;
+        Ok(result)
+    }
+
+    /// Run `op` through the typed serialization layer: write the op code,
+    /// serialize `args`, run `process_stderr`, then deserialize the reply.
+    pub async fn request<Args, Reply>(&mut self, op: u64, args: Args) -> Result<Reply>
+    where
+        Args: AsyncNixSerialize + Sync,
+        Reply: AsyncNixDeserialize,
+    {
+        self.write_u64(op).await?;
+        args.serialize(&mut self.connection).await?;
+        self.process_stderr().await?;
+        Reply::deserialize(&mut self.connection).await
+    }
+
+    pub async fn is_valid_path(&mut self, path: &str) -> Result<bool> {
+        self.request(1, path.to_string()).await // wopIsValidPath
+    }
+
+    pub async fn query_valid_paths(&mut self, paths: &HashSet<&str>) -> Result<HashSet<String>> {
+        let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        if self.protocol_version >= wire::PROTOCOL_VERSION_BUILDERS_USE_SUBSTITUTES {
+            self.request(31, (paths, false)).await // wopQueryValidPaths, buildersUseSubstitutes
+        } else {
+            self.request(31, paths).await // wopQueryValidPaths
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Async mirror of [`crate::serialize`] for [`crate::async_connection::AsyncNixStoreConnection`].
+//!
+//! Tokio's `AsyncRead`/`AsyncWrite` aren't implemented by `std::io::Read`/
+//! `Write`, so the typed (de)serialization traits can't be shared directly
+//! with the sync side; they're implemented for the same op argument/result
+//! types, except that only the sync side has an impl for `str`.
+
+use std::collections::HashSet;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::wire::{self, Result};
+
+pub trait AsyncNixSerialize {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()>;
+}
+
+pub trait AsyncNixDeserialize: Sized {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self>;
+}
+
+impl AsyncNixSerialize for u64 {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        w.write_u64_le(*self).await.map_err(wire::Error::Write)
+    }
+}
+
+impl AsyncNixDeserialize for u64 {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        r.read_u64_le().await.map_err(wire::Error::Read)
+    }
+}
+
+impl AsyncNixSerialize for bool {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        (if *self { 1u64 } else { 0u64 }).serialize(w).await
+    }
+}
+
+impl AsyncNixDeserialize for bool {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        Ok(u64::deserialize(r).await? != 0)
+    }
+}
+
+impl AsyncNixSerialize for String {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        (self.len() as u64).serialize(w).await?;
+        w.write_all(self.as_bytes()).await.map_err(wire::Error::Write)?;
+        w.write_all(&wire::NULS[..wire::padding(self.len())])
+            .await
+            .map_err(wire::Error::Write)
+    }
+}
+
+impl AsyncNixDeserialize for String {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        let len = u64::deserialize(r).await? as usize;
+        let mut buf = vec![0u8; len + wire::padding(len)];
+        r.read_exact(&mut buf).await.map_err(wire::Error::Read)?;
+        buf.truncate(len);
+        String::from_utf8(buf).map_err(wire::Error::ParseUTF8)
+    }
+}
+
+impl<T: AsyncNixSerialize + Sync> AsyncNixSerialize for Vec<T> {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        (self.len() as u64).serialize(w).await?;
+        for item in self {
+            item.serialize(w).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: AsyncNixDeserialize> AsyncNixDeserialize for Vec<T> {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        let len = u64::deserialize(r).await? as usize;
+        let mut result = Vec::with_capacity(len);
+        for _ in 0..len {
+            result.push(T::deserialize(r).await?);
+        }
+        Ok(result)
+    }
+}
+
+impl AsyncNixSerialize for HashSet<String> {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        (self.len() as u64).serialize(w).await?;
+        for item in self {
+            item.serialize(w).await?;
+        }
+        Ok(())
+    }
+}
+
+impl AsyncNixDeserialize for HashSet<String> {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        let len = u64::deserialize(r).await? as usize;
+        let mut result = HashSet::with_capacity(len);
+        for _ in 0..len {
+            result.insert(String::deserialize(r).await?);
+        }
+        Ok(result)
+    }
+}
+
+impl<A: AsyncNixSerialize + Sync, B: AsyncNixSerialize + Sync> AsyncNixSerialize for (A, B) {
+    async fn serialize<W: AsyncWrite + Unpin>(&self, w: &mut W) -> Result<()> {
+        self.0.serialize(w).await?;
+        self.1.serialize(w).await
+    }
+}
+
+impl<A: AsyncNixDeserialize, B: AsyncNixDeserialize> AsyncNixDeserialize for (A, B) {
+    async fn deserialize<R: AsyncRead + Unpin>(r: &mut R) -> Result<Self> {
+        Ok((A::deserialize(r).await?, B::deserialize(r).await?))
+    }
+}
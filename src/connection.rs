@@ -0,0 +1,254 @@
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    process::{ChildStdin, ChildStdout, Command, Stdio},
+    collections::HashSet,
+};
+
+use crate::nar;
+use crate::progress::{ProgressHandler, StderrProgressHandler};
+use crate::serialize::{NixDeserialize, NixSerialize};
+use crate::wire::{
+    self, Error, Field, Result, PROTOCOL_VERSION, WORKER_MAGIC_1, WORKER_MAGIC_2,
+    STDERR_LAST, STDERR_RESULT, STDERR_START_ACTIVITY, STDERR_STOP_ACTIVITY, STDERR_WRITE,
+};
+
+pub struct NixStoreConnection<T>
+where
+    T: Read + Write,
+{
+    connection: T,
+    /// Raw version the daemon advertised during the handshake.
+    daemon_version: u64,
+    /// `min(PROTOCOL_VERSION, daemon_version)`, the version actually spoken
+    /// on this connection; every op consults this to decide which optional
+    /// fields to read or write.
+    protocol_version: u64,
+    daemon_nix_version: String,
+    progress_handler: Box<dyn ProgressHandler>,
+}
+
+pub struct RWJoin<R, W> where R: Read, W: Write {
+    r: R,
+    w: W,
+}
+impl<R, W> Read for RWJoin<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.r.read(buf)
+    }
+}
+impl<R, W> Write for RWJoin<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.w.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.w.flush()
+    }
+}
+
+trait RW: Read + Write {}
+impl <T> RW for T where T: Read + Write {}
+
+impl<T> NixStoreConnection<T>
+where
+    T: Read + Write,
+{
+    fn read_u64(&mut self) -> Result<u64> {
+        wire::read_u64(&mut self.connection)
+    }
+
+    fn write_u64(&mut self, value: u64) -> Result<()> {
+        wire::write_u64(&mut self.connection, value)
+    }
+
+    fn init(&mut self) -> Result<()> {
+        self.write_u64(WORKER_MAGIC_1)?;
+        if self.read_u64()? != WORKER_MAGIC_2 {
+            return Err(Error::ProtocolMismatch);
+        }
+        self.daemon_version = self.read_u64()?;
+        if wire::protocol_major(self.daemon_version) != wire::protocol_major(PROTOCOL_VERSION)
+            || self.daemon_version < wire::PROTOCOL_VERSION_MIN_SUPPORTED
+        {
+            return Err(Error::UnsupportedProtocolVersion(self.daemon_version));
+        }
+        self.write_u64(PROTOCOL_VERSION)?;
+        self.protocol_version = wire::negotiate_version(PROTOCOL_VERSION, self.daemon_version);
+        if self.protocol_version >= wire::PROTOCOL_VERSION_CPU_AFFINITY {
+            self.write_u64(0)?; // obsolete CPU affinity
+        }
+        if self.protocol_version >= wire::PROTOCOL_VERSION_RESERVE_SPACE {
+            self.write_u64(0)?; // obsolete reserveSpace
+        }
+        self.connection.flush().map_err(Error::Flush)?;
+        self.daemon_nix_version = self.read_string()?;
+        if self.protocol_version >= wire::PROTOCOL_VERSION_FEATURES {
+            let num_features = self.read_u64()?;
+            for _ in 0..num_features {
+                let _feature = self.read_string()?;
+            }
+        }
+        self.process_stderr()?;
+        Ok(())
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        wire::read_string(&mut self.connection)
+    }
+
+    fn write_string(&mut self, str: &str) -> Result<()> {
+        wire::write_string(&mut self.connection, str)
+    }
+
+    fn read_fields(&mut self) -> Result<Vec<Field>> {
+        wire::read_fields(&mut self.connection)
+    }
+
+    fn process_stderr(&mut self) -> Result<()> {
+        // TODO: make flushing optional? It is in Nix
+        self.connection.flush().map_err(Error::Flush)?;
+
+        loop {
+            match self.read_u64()? {
+                STDERR_WRITE => {
+                    let s = self.read_string()?;
+                    self.progress_handler.on_message(&s);
+                }
+                STDERR_START_ACTIVITY => {
+                    let activity_id = self.read_u64()?;
+                    let level = self.read_u64()?;
+                    let activity_type = self.read_u64()?;
+                    let description = self.read_string()?;
+                    let fields = self.read_fields()?;
+                    let parent_activity_id = self.read_u64()?;
+                    self.progress_handler.on_start_activity(
+                        activity_id,
+                        level,
+                        activity_type,
+                        &description,
+                        &fields,
+                        parent_activity_id,
+                    );
+                }
+                STDERR_STOP_ACTIVITY => {
+                    let activity_id = self.read_u64()?;
+                    self.progress_handler.on_stop_activity(activity_id);
+                }
+                STDERR_LAST => {
+                    break;
+                }
+                STDERR_RESULT => {
+                    let activity_id = self.read_u64()?;
+                    let result_type = self.read_u64()?;
+                    let fields = self.read_fields()?;
+                    self.progress_handler.on_result(activity_id, result_type, &fields);
+                }
+                n => return Err(Error::UnsupportedStderrMessage(n)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Install a handler to receive progress/logging traffic instead of the
+    /// default of writing messages to stderr and discarding the rest.
+    pub fn set_progress_handler(&mut self, handler: impl ProgressHandler + 'static) {
+        self.progress_handler = Box::new(handler);
+    }
+
+    pub fn connect(connection: T) -> Result<Self> {
+        let mut result = Self {
+            connection,
+            daemon_version: 0,
+            protocol_version: 0,
+            daemon_nix_version: String::from(""),
+            progress_handler: Box::new(StderrProgressHandler),
+        };
+        result.init()?;
+        Ok(result)
+    }
+
+    /// Run `op` through the typed serialization layer: write the op code,
+    /// serialize `args`, run `process_stderr`, then deserialize the reply.
+    pub fn request<Args: NixSerialize, Reply: NixDeserialize>(
+        &mut self,
+        op: u64,
+        args: Args,
+    ) -> Result<Reply> {
+        self.write_u64(op)?;
+        args.serialize(&mut self.connection)?;
+        self.process_stderr()?;
+        Reply::deserialize(&mut self.connection)
+    }
+
+    pub fn is_valid_path(&mut self, path: &str) -> Result<bool> {
+        self.request(1, path.to_string()) // wopIsValidPath
+    }
+
+    pub fn query_valid_paths(&mut self, paths: &HashSet<&str>) -> Result<HashSet<String>> {
+        let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        if self.protocol_version >= wire::PROTOCOL_VERSION_BUILDERS_USE_SUBSTITUTES {
+            self.request(31, (paths, false)) // wopQueryValidPaths, buildersUseSubstitutes
+        } else {
+            self.request(31, paths) // wopQueryValidPaths
+        }
+    }
+
+    /// Add `path` to the store under `name`, streaming it to the daemon as
+    /// a NAR. Returns the resulting store path.
+    pub fn add_to_store(&mut self, name: &str, path: &Path) -> Result<String> {
+        self.write_u64(7)?; // wopAddToStore
+        self.write_string(name)?;
+        self.write_u64(1)?; // fixed
+        self.write_u64(1)?; // recursive
+        self.write_string("sha256")?;
+        nar::dump_path(&mut self.connection, path)?;
+        self.process_stderr()?;
+        self.read_string()
+    }
+
+    /// Ask the daemon for the NAR of `store_path` and materialize it at
+    /// `destination`.
+    pub fn nar_from_path(&mut self, store_path: &str, destination: &Path) -> Result<()> {
+        self.write_u64(38)?; // wopNarFromPath
+        self.write_string(store_path)?;
+        self.process_stderr()?;
+        nar::restore_path(&mut self.connection, destination)
+    }
+}
+
+impl NixStoreConnection<UnixStream> {
+    pub fn connect_local() -> Result<Self> {
+        let path = env::var("NIX_DAEMON_SOCKET_PATH")
+            .unwrap_or("/nix/var/nix/daemon-socket/socket".into());
+        let stream = UnixStream::connect(path).map_err(Error::Connect)?;
+        Self::connect(stream)
+    }
+}
+
+impl NixStoreConnection<RWJoin<ChildStdout, ChildStdin>> {
+    pub fn connect_to_store(uri: &str) -> Result<Self> {
+        let mut command = Command::new("nix-daemon");
+        command
+            .arg("--store")
+            .arg(uri)
+            .arg("--stdio")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        let process = command.spawn().map_err(Error::SpawnChild)?;
+        Self::connect(RWJoin {
+            r: process.stdout.unwrap(),
+            w: process.stdin.unwrap(),
+        })
+    }
+}
@@ -0,0 +1,8 @@
+pub mod async_connection;
+pub mod async_serialize;
+pub mod connection;
+pub mod nar;
+pub mod narinfo;
+pub mod progress;
+pub mod serialize;
+pub mod wire;
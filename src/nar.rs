@@ -0,0 +1,285 @@
+//! NAR (Nix ARchive) serialization.
+//!
+//! A NAR is a simple recursive format built out of the same
+//! length-prefixed, 8-byte-padded strings used for worker protocol framing
+//! (see [`crate::wire`]). It begins with the magic token `nix-archive-1`
+//! followed by a single node: `( type regular|symlink|directory ... )`.
+
+use std::{
+    fs,
+    io::{Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path},
+};
+
+use crate::wire::{self, Error, Result};
+
+const NAR_MAGIC: &str = "nix-archive-1";
+
+/// `entry ( name <name> ... )` names come straight off the wire and are
+/// joined onto the restore destination; reject anything that isn't a
+/// single plain path component so a malicious NAR (e.g. fetched from a
+/// substituter) can't escape the destination directory via `..` or an
+/// absolute path.
+fn check_entry_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(Error::NarInvalidEntryName(name.to_string())),
+    }
+}
+
+fn expect<R: Read>(reader: &mut R, expected: &'static str) -> Result<()> {
+    let found = wire::read_string(reader)?;
+    if found != expected {
+        return Err(Error::NarUnexpectedToken { expected, found });
+    }
+    Ok(())
+}
+
+/// Serialize the file, symlink, or directory at `path` as a NAR onto
+/// `writer`.
+pub fn dump_path<W: Write>(writer: &mut W, path: &Path) -> Result<()> {
+    wire::write_string(writer, NAR_MAGIC)?;
+    dump_node(writer, path)
+}
+
+fn dump_node<W: Write>(writer: &mut W, path: &Path) -> Result<()> {
+    wire::write_string(writer, "(")?;
+
+    let metadata = fs::symlink_metadata(path).map_err(Error::Io)?;
+    if metadata.is_symlink() {
+        let target = fs::read_link(path).map_err(Error::Io)?;
+        wire::write_string(writer, "type")?;
+        wire::write_string(writer, "symlink")?;
+        wire::write_string(writer, "target")?;
+        wire::write_string(writer, &target.to_string_lossy())?;
+    } else if metadata.is_dir() {
+        wire::write_string(writer, "type")?;
+        wire::write_string(writer, "directory")?;
+
+        let mut entries: Vec<_> = fs::read_dir(path)
+            .map_err(Error::Io)?
+            .collect::<std::result::Result<_, _>>()
+            .map_err(Error::Io)?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            wire::write_string(writer, "entry")?;
+            wire::write_string(writer, "(")?;
+            wire::write_string(writer, "name")?;
+            wire::write_string(writer, &entry.file_name().to_string_lossy())?;
+            wire::write_string(writer, "node")?;
+            dump_node(writer, &entry.path())?;
+            wire::write_string(writer, ")")?;
+        }
+    } else if metadata.is_file() {
+        wire::write_string(writer, "type")?;
+        wire::write_string(writer, "regular")?;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            wire::write_string(writer, "executable")?;
+            wire::write_string(writer, "")?;
+        }
+        wire::write_string(writer, "contents")?;
+        let contents = fs::read(path).map_err(Error::Io)?;
+        wire::write_bytes(writer, &contents)?;
+    } else {
+        return Err(Error::NarUnsupportedFileType);
+    }
+
+    wire::write_string(writer, ")")
+}
+
+/// Materialize a NAR read from `reader` at `path`, which must not already
+/// exist.
+pub fn restore_path<R: Read>(reader: &mut R, path: &Path) -> Result<()> {
+    expect(reader, NAR_MAGIC)?;
+    restore_node(reader, path)
+}
+
+fn restore_node<R: Read>(reader: &mut R, path: &Path) -> Result<()> {
+    expect(reader, "(")?;
+    expect(reader, "type")?;
+
+    match wire::read_string(reader)?.as_str() {
+        "symlink" => {
+            expect(reader, "target")?;
+            let target = wire::read_string(reader)?;
+            std::os::unix::fs::symlink(target, path).map_err(Error::Io)?;
+        }
+        "directory" => {
+            fs::create_dir(path).map_err(Error::Io)?;
+            loop {
+                match wire::read_string(reader)?.as_str() {
+                    "entry" => {
+                        expect(reader, "(")?;
+                        expect(reader, "name")?;
+                        let name = wire::read_string(reader)?;
+                        check_entry_name(&name)?;
+                        expect(reader, "node")?;
+                        restore_node(reader, &path.join(name))?;
+                        expect(reader, ")")?;
+                    }
+                    ")" => break,
+                    found => {
+                        return Err(Error::NarUnexpectedToken {
+                            expected: "entry or )",
+                            found: found.to_string(),
+                        })
+                    }
+                }
+            }
+            return Ok(());
+        }
+        "regular" => {
+            let mut executable = false;
+            loop {
+                match wire::read_string(reader)?.as_str() {
+                    "executable" => {
+                        expect(reader, "")?;
+                        executable = true;
+                    }
+                    "contents" => {
+                        let contents = wire::read_bytes(reader)?;
+                        fs::write(path, &contents).map_err(Error::Io)?;
+                        break;
+                    }
+                    found => {
+                        return Err(Error::NarUnexpectedToken {
+                            expected: "executable or contents",
+                            found: found.to_string(),
+                        })
+                    }
+                }
+            }
+            if executable {
+                let mut perms = fs::metadata(path).map_err(Error::Io)?.permissions();
+                let mode = perms.mode();
+                perms.set_mode(mode | 0o111);
+                fs::set_permissions(path, perms).map_err(Error::Io)?;
+            }
+        }
+        found => {
+            return Err(Error::NarUnexpectedToken {
+                expected: "regular, symlink or directory",
+                found: found.to_string(),
+            })
+        }
+    }
+
+    expect(reader, ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    /// A fresh, empty directory under the OS temp dir, removed on drop.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicU64 = AtomicU64::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "nixstore-rs-nar-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn check_entry_name_rejects_path_traversal() {
+        assert!(matches!(
+            check_entry_name("../evil"),
+            Err(Error::NarInvalidEntryName(_))
+        ));
+    }
+
+    #[test]
+    fn check_entry_name_rejects_absolute_paths() {
+        assert!(matches!(
+            check_entry_name("/abs"),
+            Err(Error::NarInvalidEntryName(_))
+        ));
+    }
+
+    #[test]
+    fn check_entry_name_accepts_a_plain_component() {
+        assert!(check_entry_name("hello-2.12.1").is_ok());
+    }
+
+    #[test]
+    fn restore_path_rejects_a_traversal_entry_name() {
+        let src = TempDir::new();
+        // Build the NAR by hand: a directory containing an entry whose name
+        // is a traversal attempt, which `dump_path` itself would never emit.
+        let mut nar = Vec::new();
+        wire::write_string(&mut nar, NAR_MAGIC).unwrap();
+        wire::write_string(&mut nar, "(").unwrap();
+        wire::write_string(&mut nar, "type").unwrap();
+        wire::write_string(&mut nar, "directory").unwrap();
+        wire::write_string(&mut nar, "entry").unwrap();
+        wire::write_string(&mut nar, "(").unwrap();
+        wire::write_string(&mut nar, "name").unwrap();
+        wire::write_string(&mut nar, "../evil").unwrap();
+        wire::write_string(&mut nar, "node").unwrap();
+        wire::write_string(&mut nar, "(").unwrap();
+        wire::write_string(&mut nar, "type").unwrap();
+        wire::write_string(&mut nar, "regular").unwrap();
+        wire::write_string(&mut nar, "contents").unwrap();
+        wire::write_bytes(&mut nar, b"payload").unwrap();
+        wire::write_string(&mut nar, ")").unwrap();
+        wire::write_string(&mut nar, ")").unwrap();
+        wire::write_string(&mut nar, ")").unwrap();
+
+        let dest = src.0.join("dest");
+        let err = restore_path(&mut nar.as_slice(), &dest).unwrap_err();
+        assert!(matches!(err, Error::NarInvalidEntryName(name) if name == "../evil"));
+        assert!(!dest.parent().unwrap().join("evil").exists());
+    }
+
+    #[test]
+    fn dump_and_restore_round_trip_a_file_tree() {
+        let src = TempDir::new();
+        fs::write(src.0.join("regular"), b"hello world").unwrap();
+        std::os::unix::fs::symlink("regular", src.0.join("link")).unwrap();
+        fs::create_dir(src.0.join("subdir")).unwrap();
+        fs::write(src.0.join("subdir/nested"), b"nested contents").unwrap();
+        let mut perms = fs::metadata(src.0.join("subdir/nested")).unwrap().permissions();
+        perms.set_mode(perms.mode() | 0o111);
+        fs::set_permissions(src.0.join("subdir/nested"), perms).unwrap();
+
+        let mut nar = Vec::new();
+        dump_path(&mut nar, &src.0).unwrap();
+
+        let dest = TempDir::new();
+        let restored = dest.0.join("restored");
+        restore_path(&mut nar.as_slice(), &restored).unwrap();
+
+        assert_eq!(fs::read(restored.join("regular")).unwrap(), b"hello world");
+        assert_eq!(
+            fs::read_link(restored.join("link")).unwrap(),
+            Path::new("regular")
+        );
+        assert_eq!(
+            fs::read(restored.join("subdir/nested")).unwrap(),
+            b"nested contents"
+        );
+        let nested_mode = fs::metadata(restored.join("subdir/nested"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_ne!(nested_mode & 0o111, 0);
+    }
+}
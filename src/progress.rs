@@ -0,0 +1,42 @@
+use std::io::Write;
+
+use crate::wire::Field;
+
+/// Receives the progress/logging traffic the daemon interleaves with every
+/// worker op (`STDERR_WRITE`, `STDERR_START_ACTIVITY`, `STDERR_STOP_ACTIVITY`,
+/// `STDERR_RESULT`).
+///
+/// Implement this to drive progress bars or structured tracing instead of
+/// the default behavior of writing plain messages to stderr and discarding
+/// everything else. All methods have a no-op default so handlers only need
+/// to override what they care about.
+pub trait ProgressHandler {
+    fn on_message(&mut self, _message: &str) {}
+
+    #[allow(clippy::too_many_arguments)]
+    fn on_start_activity(
+        &mut self,
+        _id: u64,
+        _level: u64,
+        _activity_type: u64,
+        _description: &str,
+        _fields: &[Field],
+        _parent: u64,
+    ) {
+    }
+
+    fn on_stop_activity(&mut self, _id: u64) {}
+
+    fn on_result(&mut self, _id: u64, _result_type: u64, _fields: &[Field]) {}
+}
+
+/// The historical behavior: `STDERR_WRITE` messages go to stderr, everything
+/// else is discarded. Used when a connection has no handler installed.
+pub struct StderrProgressHandler;
+
+impl ProgressHandler for StderrProgressHandler {
+    fn on_message(&mut self, message: &str) {
+        // Best-effort: a failed write to stderr is ignored, not reported.
+        let _ = std::io::stderr().write_all(message.as_bytes());
+    }
+}
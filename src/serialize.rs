@@ -0,0 +1,150 @@
+//! A small serde-like framework over the wire primitives in [`crate::wire`].
+//!
+//! Hand-interleaving `write_u64`/`write_string`/`read_u64` for every worker
+//! op is error-prone and doesn't scale as more ops are added. Instead, op
+//! argument and result types implement [`NixSerialize`]/[`NixDeserialize`],
+//! and [`crate::connection::NixStoreConnection::request`] drives them
+//! (including running `process_stderr` at the right point).
+
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use crate::wire::{self, Result};
+
+pub trait NixSerialize {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()>;
+}
+
+pub trait NixDeserialize: Sized {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self>;
+}
+
+impl NixSerialize for u64 {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        wire::write_u64(w, *self)
+    }
+}
+
+impl NixDeserialize for u64 {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        wire::read_u64(r)
+    }
+}
+
+impl NixSerialize for bool {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        wire::write_u64(w, if *self { 1 } else { 0 })
+    }
+}
+
+impl NixDeserialize for bool {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(wire::read_u64(r)? != 0)
+    }
+}
+
+impl NixSerialize for String {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        wire::write_string(w, self)
+    }
+}
+
+impl NixDeserialize for String {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        wire::read_string(r)
+    }
+}
+
+impl NixSerialize for str {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        wire::write_string(w, self)
+    }
+}
+
+impl<T: NixSerialize> NixSerialize for Vec<T> {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        wire::write_u64(w, self.len() as u64)?;
+        for item in self {
+            item.serialize(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: NixDeserialize> NixDeserialize for Vec<T> {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let len = wire::read_u64(r)? as usize;
+        let mut result = Vec::with_capacity(len);
+        for _ in 0..len {
+            result.push(T::deserialize(r)?);
+        }
+        Ok(result)
+    }
+}
+
+impl NixSerialize for HashSet<String> {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        wire::write_u64(w, self.len() as u64)?;
+        for item in self {
+            item.serialize(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl NixDeserialize for HashSet<String> {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let len = wire::read_u64(r)? as usize;
+        let mut result = HashSet::with_capacity(len);
+        for _ in 0..len {
+            result.insert(String::deserialize(r)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<A: NixSerialize, B: NixSerialize> NixSerialize for (A, B) {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.0.serialize(w)?;
+        self.1.serialize(w)
+    }
+}
+
+impl<A: NixDeserialize, B: NixDeserialize> NixDeserialize for (A, B) {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        Ok((A::deserialize(r)?, B::deserialize(r)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: NixSerialize + NixDeserialize + PartialEq + std::fmt::Debug>(value: T) {
+        let mut buf = Vec::new();
+        value.serialize(&mut buf).unwrap();
+        assert_eq!(T::deserialize(&mut buf.as_slice()).unwrap(), value);
+    }
+
+    #[test]
+    fn round_trips_a_vec_of_strings() {
+        round_trip(vec!["a".to_string(), "bb".to_string(), "ccc".to_string()]);
+        round_trip(Vec::<String>::new());
+    }
+
+    #[test]
+    fn round_trips_a_hash_set() {
+        let mut set = HashSet::new();
+        set.insert("a".to_string());
+        set.insert("bb".to_string());
+        round_trip(set);
+    }
+
+    #[test]
+    fn round_trips_a_tuple() {
+        round_trip((42u64, "hello".to_string()));
+        round_trip((vec![1u64, 2u64], true));
+    }
+}
@@ -0,0 +1,175 @@
+//! Shared framing primitives for the nix-daemon worker protocol.
+//!
+//! Both the blocking and async connection types speak the same wire
+//! format (little-endian u64s, 8-byte-padded length-prefixed strings),
+//! so the framing constants and pure helpers live here instead of being
+//! duplicated between them. The NAR format ([`crate::nar`]) reuses the
+//! same length-prefixed, 8-byte-padded string framing for its own
+//! blocking reader/writer, so the synchronous helpers below are generic
+//! over any `Read`/`Write`, not just a `NixStoreConnection`.
+
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+pub const WORKER_MAGIC_1: u64 = 0x6e697863;
+pub const WORKER_MAGIC_2: u64 = 0x6478696f;
+
+pub const STDERR_NEXT: u64 = 0x6f6c6d67;
+pub const STDERR_READ: u64 = 0x64617461;
+pub const STDERR_WRITE: u64 = 0x64617416;
+pub const STDERR_LAST: u64 = 0x616c7473;
+pub const STDERR_ERROR: u64 = 0x63787470;
+pub const STDERR_START_ACTIVITY: u64 = 0x53545254;
+pub const STDERR_STOP_ACTIVITY: u64 = 0x53544f50;
+pub const STDERR_RESULT: u64 = 0x52534c54;
+
+/// Newest protocol version this crate speaks. The effective version used on
+/// any given connection is the minimum of this and the daemon's advertised
+/// version; see [`negotiate_version`].
+pub const PROTOCOL_VERSION: u64 = 0x0100 | 37;
+
+/// From this version on, the post-handshake exchange includes the obsolete
+/// CPU-affinity u64 (always written as 0 by modern clients).
+pub const PROTOCOL_VERSION_CPU_AFFINITY: u64 = 0x0100 | 11;
+/// From this version on, the post-handshake exchange includes the obsolete
+/// reserve-space u64 (always written as 0 by modern clients).
+pub const PROTOCOL_VERSION_RESERVE_SPACE: u64 = 0x0100 | 10;
+/// From this version on, the daemon sends a trailing set of feature strings
+/// after `daemon_nix_version` during the handshake.
+pub const PROTOCOL_VERSION_FEATURES: u64 = 0x0100 | 35;
+/// From this version on, `wopQueryValidPaths` takes a trailing
+/// `buildersUseSubstitutes` flag.
+pub const PROTOCOL_VERSION_BUILDERS_USE_SUBSTITUTES: u64 = 0x0100 | 12;
+/// Oldest daemon protocol version this crate can drive at all.
+pub const PROTOCOL_VERSION_MIN_SUPPORTED: u64 = 0x0100 | 10;
+
+pub const NULS: [u8; 8] = [0u8; 8];
+
+pub const fn protocol_major(version: u64) -> u64 {
+    version & 0xff00
+}
+
+pub const fn protocol_minor(version: u64) -> u64 {
+    version & 0x00ff
+}
+
+/// Compute the effective protocol version for a connection: the lower of
+/// what we speak and what the daemon advertised. Callers should reject the
+/// daemon version beforehand if its major version isn't one we understand.
+pub const fn negotiate_version(client: u64, daemon: u64) -> u64 {
+    if protocol_minor(client) < protocol_minor(daemon) {
+        client
+    } else {
+        daemon
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Field {
+    Int(u64),
+    String(String),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Connect(std::io::Error),
+    Read(std::io::Error),
+    Write(std::io::Error),
+    Flush(std::io::Error),
+    ParseUTF8(std::string::FromUtf8Error),
+    ProtocolMismatch,
+    Unimplemented,
+    UnsupportedProtocolVersion(u64),
+    SpawnChild(std::io::Error),
+    UnsupportedFieldType(u64),
+    UnsupportedStderrMessage(u64),
+    Io(std::io::Error),
+    NarMagicMismatch,
+    NarUnexpectedToken { expected: &'static str, found: String },
+    NarUnsupportedFileType,
+    NarInvalidEntryName(String),
+    NarInfoMissingField(&'static str),
+    NarInfoMalformedLine(String),
+    NarInfoInvalidKey(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Number of zero bytes needed to pad `len` up to the next multiple of 8.
+pub const fn padding(len: usize) -> usize {
+    (8 - len % 8) % 8
+}
+
+/// Read a little-endian u64 off `r`. Shared by the sync connection and the
+/// NAR reader, which use the same framing.
+pub fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    r.read_u64::<LittleEndian>().map_err(Error::Read)
+}
+
+/// Write a little-endian u64 to `w`. Shared by the sync connection and the
+/// NAR writer, which use the same framing.
+pub fn write_u64<W: Write>(w: &mut W, value: u64) -> Result<()> {
+    w.write_u64::<LittleEndian>(value).map_err(Error::Write)
+}
+
+/// Read a length-prefixed, 8-byte-padded byte blob off `r`.
+pub fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    let len = read_u64(r)? as usize;
+    let mut buf = vec![0u8; len + padding(len)];
+    r.read_exact(&mut buf).map_err(Error::Read)?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Write `data` as a length-prefixed, 8-byte-padded byte blob to `w`.
+pub fn write_bytes<W: Write>(w: &mut W, data: &[u8]) -> Result<()> {
+    write_u64(w, data.len() as u64)?;
+    w.write_all(data).map_err(Error::Write)?;
+    w.write_all(&NULS[..padding(data.len())]).map_err(Error::Write)?;
+    Ok(())
+}
+
+/// Read a length-prefixed, 8-byte-padded UTF-8 string off `r`.
+pub fn read_string<R: Read>(r: &mut R) -> Result<String> {
+    String::from_utf8(read_bytes(r)?).map_err(Error::ParseUTF8)
+}
+
+/// Write `s` as a length-prefixed, 8-byte-padded string to `w`.
+pub fn write_string<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    write_bytes(w, s.as_bytes())
+}
+
+/// Read a `Field` array (the `tInt`/`tString` encoding used by
+/// `STDERR_START_ACTIVITY`/`STDERR_RESULT`).
+pub fn read_fields<R: Read>(r: &mut R) -> Result<Vec<Field>> {
+    let num_fields = read_u64(r)?;
+    let mut result = Vec::with_capacity(num_fields as usize);
+    for _ in 0..num_fields {
+        let field_type = read_u64(r)?;
+        result.push(match field_type {
+            0 => Field::Int(read_u64(r)?),
+            1 => Field::String(read_string(r)?),
+            _ => return Err(Error::UnsupportedFieldType(field_type)),
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_version_picks_the_older_minor() {
+        // Client newer than daemon: use the daemon's.
+        assert_eq!(negotiate_version(PROTOCOL_VERSION, 0x0100 | 10), 0x0100 | 10);
+        // Daemon newer than client: use the client's.
+        assert_eq!(negotiate_version(0x0100 | 10, PROTOCOL_VERSION), 0x0100 | 10);
+        // Same version either way.
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION, PROTOCOL_VERSION),
+            PROTOCOL_VERSION
+        );
+    }
+}